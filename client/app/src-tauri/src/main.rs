@@ -2,7 +2,9 @@
 
 use tauri::State;
 use tokio::sync::Mutex;
-use voice_changer_core::{start_stream, AudioConfig, StreamHandle};
+use voice_changer_core::{
+    start_stream, AudioCodec, AudioConfig, DeviceInfo, StreamHandle, TransportConfig,
+};
 
 struct AppState {
     handle: Option<StreamHandle>,
@@ -15,17 +17,53 @@ impl AppState {
 }
 
 #[tauri::command]
-async fn start_stream_cmd(state: State<'_, Mutex<AppState>>, url: String) -> Result<(), String> {
+async fn start_stream_cmd(
+    state: State<'_, Mutex<AppState>>,
+    url: String,
+    input_device_name: Option<String>,
+    output_device_name: Option<String>,
+    encryption_key: Option<String>,
+    opus_bitrate: Option<i32>,
+    pitch_shift: Option<f32>,
+) -> Result<(), String> {
     let mut guard = state.lock().await;
     if guard.handle.is_some() {
         return Ok(());
     }
-    let cfg = AudioConfig { sample_rate: 48000, channels: 1, frame_size: 480 };
-    let handle = start_stream(&url, cfg).await.map_err(|e| e.to_string())?;
+    // `opus_bitrate` is the toggle: omit it for plain S16LE, pass a bitrate to encode.
+    let codec = match opus_bitrate {
+        Some(bitrate) => AudioCodec::Opus { bitrate },
+        None => AudioCodec::Pcm,
+    };
+    let cfg = AudioConfig {
+        sample_rate: 48000,
+        channels: 1,
+        frame_size: 480,
+        codec,
+        pitch_shift: pitch_shift.unwrap_or(0.0),
+        input_device_name,
+        output_device_name,
+        jitter_target_ms: 60,
+    };
+    // An empty key means the field was left blank -- treat it as no encryption
+    // rather than handing the cipher a zero-length key.
+    let psk = encryption_key.filter(|k| !k.is_empty()).map(String::into_bytes);
+    let transport = TransportConfig::WebSocket { url, psk };
+    let handle = start_stream(transport, cfg).await.map_err(|e| e.to_string())?;
     guard.handle = Some(handle);
     Ok(())
 }
 
+#[tauri::command]
+fn list_input_devices() -> Result<Vec<DeviceInfo>, String> {
+    voice_changer_core::list_input_devices().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn list_output_devices() -> Result<Vec<DeviceInfo>, String> {
+    voice_changer_core::list_output_devices().map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 async fn stop_stream_cmd(state: State<'_, Mutex<AppState>>) -> Result<(), String> {
     let mut guard = state.lock().await;
@@ -38,7 +76,12 @@ async fn stop_stream_cmd(state: State<'_, Mutex<AppState>>) -> Result<(), String
 fn main() {
     tauri::Builder::default()
         .manage(Mutex::new(AppState::new()))
-        .invoke_handler(tauri::generate_handler![start_stream_cmd, stop_stream_cmd])
+        .invoke_handler(tauri::generate_handler![
+            start_stream_cmd,
+            stop_stream_cmd,
+            list_input_devices,
+            list_output_devices
+        ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
@@ -0,0 +1,134 @@
+//! Adaptive playback jitter buffer.
+//!
+//! The old playback path drained an `mpsc` queue and zero-filled the rest of
+//! the output buffer the moment it ran dry, which produces audible gaps
+//! under ordinary network jitter. This replaces it with a ring buffer that
+//! targets a configurable fill depth, fades out on underrun instead of
+//! cutting to hard silence, and drops the oldest buffered audio once it
+//! grows past a high watermark so latency stays bounded under bursty
+//! arrival.
+
+use ringbuf::{HeapConsumer, HeapProducer, HeapRb};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Once the backlog exceeds this multiple of the target depth, the writer
+/// starts dropping the oldest samples rather than let latency grow unbounded.
+const HIGH_WATERMARK_MULTIPLIER: usize = 4;
+
+/// Current buffer fill, in samples, shared between the writer/reader and
+/// whoever reports it (the stats message in `start_stream`'s main loop).
+#[derive(Clone)]
+pub struct FillLevel(Arc<AtomicUsize>);
+
+impl FillLevel {
+    fn new() -> Self {
+        Self(Arc::new(AtomicUsize::new(0)))
+    }
+
+    pub fn samples(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+pub struct JitterWriter {
+    producer: HeapProducer<i16>,
+    fill: FillLevel,
+}
+
+impl JitterWriter {
+    /// Push freshly-decoded samples. The ring buffer is sized to the high
+    /// watermark, so once it's full `push_overwrite` drops the oldest
+    /// buffered sample for each new one pushed -- that's a producer-side
+    /// operation ringbuf actually supports, unlike trying to have the
+    /// producer `skip` (which only ever advances a consumer's read cursor).
+    pub fn push(&mut self, samples: &[i16]) {
+        for &s in samples {
+            self.producer.push_overwrite(s);
+        }
+        self.fill.0.store(self.producer.len(), Ordering::Relaxed);
+    }
+}
+
+pub struct JitterReader {
+    consumer: HeapConsumer<i16>,
+    fill: FillLevel,
+    target: usize,
+    last_sample: i16,
+    primed: bool,
+}
+
+impl JitterReader {
+    /// A cloneable handle to this reader's current fill level, in samples.
+    pub fn fill_level(&self) -> FillLevel {
+        self.fill.clone()
+    }
+
+    /// Fill `out` with mono samples from the buffer. Until the buffer has
+    /// built up to its target depth (initially, or after an underrun) this
+    /// fades from the last played sample instead of resuming immediately,
+    /// to avoid chattering in and out under sustained jitter.
+    pub fn fill(&mut self, out: &mut [i16]) {
+        if !self.primed && self.consumer.len() < self.target {
+            self.fade_out(out);
+            self.fill.0.store(self.consumer.len(), Ordering::Relaxed);
+            return;
+        }
+        self.primed = true;
+
+        let mut idx = 0;
+        while idx < out.len() {
+            match self.consumer.pop() {
+                Some(s) => {
+                    out[idx] = s;
+                    self.last_sample = s;
+                    idx += 1;
+                }
+                None => {
+                    self.primed = false;
+                    break;
+                }
+            }
+        }
+        if idx < out.len() {
+            self.fade_out(&mut out[idx..]);
+        }
+        self.fill.0.store(self.consumer.len(), Ordering::Relaxed);
+    }
+
+    /// Underrun handling: ramp from the last played sample down to silence
+    /// rather than inserting a hard discontinuity.
+    fn fade_out(&mut self, out: &mut [i16]) {
+        let len = out.len();
+        for (i, slot) in out.iter_mut().enumerate() {
+            let fade = 1.0 - (i as f32 + 1.0) / (len as f32 + 1.0);
+            *slot = (self.last_sample as f32 * fade) as i16;
+        }
+        self.last_sample = 0;
+    }
+}
+
+/// Build a jitter buffer sized for `target_samples` of steady-state depth.
+/// Its capacity is the high watermark: once full, `JitterWriter::push`
+/// overwrites the oldest buffered sample rather than growing without bound.
+///
+/// `target_samples` is clamped to at least 1 so a misconfigured (or rounded
+/// down to zero) `jitter_target_ms` can't hand a zero-capacity ring buffer
+/// to `HeapRb::new`.
+pub fn channel(target_samples: usize) -> (JitterWriter, JitterReader) {
+    let target_samples = target_samples.max(1);
+    let high_watermark = (target_samples * HIGH_WATERMARK_MULTIPLIER).max(target_samples * 2);
+    let rb = HeapRb::<i16>::new(high_watermark);
+    let (producer, consumer) = rb.split();
+    let fill = FillLevel::new();
+    (
+        JitterWriter { producer, fill: fill.clone() },
+        JitterReader {
+            consumer,
+            fill,
+            target: target_samples,
+            last_sample: 0,
+            primed: false,
+        },
+    )
+}
@@ -1,20 +1,40 @@
 use anyhow::{anyhow, Result};
+use audiopus::coder::{Decoder as OpusDecoder, Encoder as OpusEncoder};
+use audiopus::{Application as OpusApplication, Channels as OpusChannels, SampleRate as OpusSampleRate};
 use bytes::Bytes;
-use futures_util::{SinkExt, StreamExt};
 use serde::Serialize;
-use std::sync::{Arc, Mutex}; // Use the standard library's Mutex for synchronous contexts
 use std::sync::mpsc as std_mpsc;
 use std::thread::JoinHandle;
 use tokio::sync::mpsc;
-use tokio_tungstenite::connect_async;
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
 use cpal::{SampleFormat, SampleRate, StreamConfig};
 
+mod phase_vocoder;
+use phase_vocoder::PhaseVocoder;
+
+mod transport;
+pub use transport::TransportConfig;
+
+mod jitter;
+
+/// Wire codec for the captured/played audio. `Opus` trades a little CPU for
+/// roughly a 10x smaller stream compared to raw `S16LE`.
+#[derive(Clone, Debug)]
+pub enum AudioCodec {
+    Pcm,
+    Opus { bitrate: i32 },
+}
+
 #[derive(Clone, Debug)]
 pub struct AudioConfig {
     pub sample_rate: u32,  // 48000
     pub channels: u16,     // 1
     pub frame_size: u32,   // 480 samples (10ms)
+    pub codec: AudioCodec,
+    pub pitch_shift: f32,  // semitones; 0.0 disables the phase vocoder
+    pub input_device_name: Option<String>,
+    pub output_device_name: Option<String>,
+    pub jitter_target_ms: u32, // target playback buffer depth, e.g. 40-80ms
 }
 
 // Use snake_case for fields and add the serde attribute to keep the JSON output as camelCase.
@@ -26,6 +46,121 @@ struct InitMessage<'a> {
     channels: u16,
     format: &'a str,
     frame_size: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bitrate: Option<i32>,
+    encrypted: bool,
+}
+
+fn opus_sample_rate(hz: u32) -> Result<OpusSampleRate> {
+    match hz {
+        8000 => Ok(OpusSampleRate::Hz8000),
+        12000 => Ok(OpusSampleRate::Hz12000),
+        16000 => Ok(OpusSampleRate::Hz16000),
+        24000 => Ok(OpusSampleRate::Hz24000),
+        48000 => Ok(OpusSampleRate::Hz48000),
+        other => Err(anyhow!("unsupported sample rate for Opus: {other}")),
+    }
+}
+
+fn opus_channels(channels: u16) -> Result<OpusChannels> {
+    match channels {
+        1 => Ok(OpusChannels::Mono),
+        2 => Ok(OpusChannels::Stereo),
+        other => Err(anyhow!("unsupported channel count for Opus: {other}")),
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SupportedConfigInfo {
+    pub channels: u16,
+    pub min_sample_rate: u32,
+    pub max_sample_rate: u32,
+    pub sample_format: String,
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceInfo {
+    pub name: String,
+    pub configs: Vec<SupportedConfigInfo>,
+}
+
+fn config_info(range: cpal::SupportedStreamConfigRange) -> SupportedConfigInfo {
+    SupportedConfigInfo {
+        channels: range.channels(),
+        min_sample_rate: range.min_sample_rate().0,
+        max_sample_rate: range.max_sample_rate().0,
+        sample_format: format!("{:?}", range.sample_format()),
+    }
+}
+
+/// List input devices on the default host, each with its supported configs.
+pub fn list_input_devices() -> Result<Vec<DeviceInfo>> {
+    let host = cpal::default_host();
+    let devices = host
+        .input_devices()
+        .map_err(|e| anyhow!("failed to enumerate input devices: {e}"))?;
+    Ok(devices
+        .map(|device| {
+            let name = device.name().unwrap_or_else(|_| "<unknown>".to_string());
+            let configs = device
+                .supported_input_configs()
+                .map(|it| it.map(config_info).collect())
+                .unwrap_or_default();
+            DeviceInfo { name, configs }
+        })
+        .collect())
+}
+
+/// List output devices on the default host, each with its supported configs.
+pub fn list_output_devices() -> Result<Vec<DeviceInfo>> {
+    let host = cpal::default_host();
+    let devices = host
+        .output_devices()
+        .map_err(|e| anyhow!("failed to enumerate output devices: {e}"))?;
+    Ok(devices
+        .map(|device| {
+            let name = device.name().unwrap_or_else(|_| "<unknown>".to_string());
+            let configs = device
+                .supported_output_configs()
+                .map(|it| it.map(config_info).collect())
+                .unwrap_or_default();
+            DeviceInfo { name, configs }
+        })
+        .collect())
+}
+
+/// Find an input device by exact name, falling back to the host default
+/// if no name is given, or (with a warning) if the name matches nothing.
+fn select_input_device(host: &cpal::Host, name: Option<&str>) -> Option<cpal::Device> {
+    if let Some(name) = name {
+        let found = host
+            .input_devices()
+            .ok()
+            .and_then(|mut devices| devices.find(|d| d.name().map(|n| n == name).unwrap_or(false)));
+        if found.is_some() {
+            return found;
+        }
+        eprintln!("input device '{name}' not found, falling back to default");
+    }
+    host.default_input_device()
+}
+
+/// Find an output device by exact name, falling back to the host default
+/// if no name is given, or (with a warning) if the name matches nothing.
+fn select_output_device(host: &cpal::Host, name: Option<&str>) -> Option<cpal::Device> {
+    if let Some(name) = name {
+        let found = host
+            .output_devices()
+            .ok()
+            .and_then(|mut devices| devices.find(|d| d.name().map(|n| n == name).unwrap_or(false)));
+        if found.is_some() {
+            return found;
+        }
+        eprintln!("output device '{name}' not found, falling back to default");
+    }
+    host.default_output_device()
 }
 
 pub struct StreamHandle {
@@ -41,10 +176,9 @@ impl StreamHandle {
     }
 }
 
-pub async fn start_stream(ws_url: &str, cfg: AudioConfig) -> Result<StreamHandle> {
+pub async fn start_stream(transport_config: TransportConfig, cfg: AudioConfig) -> Result<StreamHandle> {
     // Stop signal for the dedicated worker thread
     let (stop_tx, stop_rx) = std_mpsc::channel::<()>();
-    let ws_url_owned = ws_url.to_string();
 
     // Spawn a dedicated OS thread to own CPAL streams and a single-threaded Tokio runtime
     let worker: JoinHandle<()> = std::thread::spawn(move || {
@@ -56,11 +190,11 @@ pub async fn start_stream(ws_url: &str, cfg: AudioConfig) -> Result<StreamHandle
         rt.block_on(async move {
             // Set up audio I/O with cpal
             let host = cpal::default_host();
-            let input_device = match host.default_input_device() {
+            let input_device = match select_input_device(&host, cfg.input_device_name.as_deref()) {
                 Some(d) => d,
                 None => { eprintln!("No default input device"); return; }
             };
-            let output_device = match host.default_output_device() {
+            let output_device = match select_output_device(&host, cfg.output_device_name.as_deref()) {
                 Some(d) => d,
                 None => { eprintln!("No default output device"); return; }
             };
@@ -138,13 +272,58 @@ pub async fn start_stream(ws_url: &str, cfg: AudioConfig) -> Result<StreamHandle
                 (cfg2, fmt)
             };
 
-            // Channels between input callback and ws task
+            // Channel carrying outgoing audio frames from the input callback to the ws task
             let (frame_tx, mut frame_rx) = mpsc::channel::<Bytes>(64);
-            let (play_tx, play_rx) = mpsc::channel::<Bytes>(64);
-            let play_rx = Arc::new(Mutex::new(play_rx));
+
+            // Jitter buffer carrying incoming decoded audio to the output callback
+            let jitter_target_samples =
+                (cfg.sample_rate as u64 * cfg.channels as u64 * cfg.jitter_target_ms as u64 / 1000) as usize;
+            let (mut jitter_writer, jitter_reader) = jitter::channel(jitter_target_samples);
+            let jitter_fill = jitter_reader.fill_level();
+
+            // Opus encoder/decoder, built once up front so the hot paths below just call `.encode`/`.decode`.
+            let mut opus_encoder: Option<OpusEncoder> = match &cfg.codec {
+                AudioCodec::Pcm => None,
+                AudioCodec::Opus { bitrate } => {
+                    match opus_sample_rate(cfg.sample_rate).and_then(|sr| {
+                        opus_channels(cfg.channels).map(|ch| (sr, ch))
+                    }) {
+                        Ok((sr, ch)) => match OpusEncoder::new(sr, ch, OpusApplication::Voip) {
+                            Ok(mut enc) => {
+                                if let Err(e) = enc.set_bitrate(audiopus::Bitrate::BitsPerSecond(*bitrate)) {
+                                    eprintln!("opus set_bitrate failed: {e}");
+                                }
+                                Some(enc)
+                            }
+                            Err(e) => { eprintln!("failed to build opus encoder: {e}"); None }
+                        },
+                        Err(e) => { eprintln!("{e}"); None }
+                    }
+                }
+            };
+            let mut opus_decoder: Option<OpusDecoder> = match &cfg.codec {
+                AudioCodec::Pcm => None,
+                AudioCodec::Opus { .. } => {
+                    match opus_sample_rate(cfg.sample_rate).and_then(|sr| {
+                        opus_channels(cfg.channels).map(|ch| (sr, ch))
+                    }) {
+                        Ok((sr, ch)) => match OpusDecoder::new(sr, ch) {
+                            Ok(dec) => Some(dec),
+                            Err(e) => { eprintln!("failed to build opus decoder: {e}"); None }
+                        },
+                        Err(e) => { eprintln!("{e}"); None }
+                    }
+                }
+            };
 
             // Build input stream
             let mut input_accum: Vec<i16> = Vec::with_capacity((cfg.frame_size * cfg.channels as u32) as usize);
+            let mut opus_out_buf = vec![0u8; 4000];
+            let mut vocoder = PhaseVocoder::new(cfg.pitch_shift);
+            // The vocoder doesn't guarantee its output lands on a fixed frame size, but
+            // Opus only accepts a handful of standard frame durations -- re-chunk back
+            // to `samples_per_frame` here so the two features compose.
+            let mut vocoder_out_accum: Vec<i16> = Vec::new();
             let input_stream = {
                 let cfg_clone = cfg.clone();
                 let in_channels = input_config.channels as usize;
@@ -165,9 +344,23 @@ pub async fn start_stream(ws_url: &str, cfg: AudioConfig) -> Result<StreamHandle
                         let samples_per_frame = (cfg_clone.frame_size * cfg_clone.channels as u32) as usize;
                         while input_accum.len() >= samples_per_frame {
                             let chunk = input_accum.drain(..samples_per_frame).collect::<Vec<i16>>();
-                            let bytes = bytemuck::cast_slice(&chunk).to_vec();
-                            // Use try_send to avoid blocking the audio thread
-                            let _ = frame_tx.try_send(Bytes::from(bytes));
+                            let shifted = vocoder.process(&chunk);
+                            vocoder_out_accum.extend_from_slice(&shifted);
+
+                            while vocoder_out_accum.len() >= samples_per_frame {
+                                let chunk = vocoder_out_accum
+                                    .drain(..samples_per_frame)
+                                    .collect::<Vec<i16>>();
+                                let bytes = match opus_encoder.as_mut() {
+                                    Some(enc) => match enc.encode(&chunk, &mut opus_out_buf) {
+                                        Ok(n) => opus_out_buf[..n].to_vec(),
+                                        Err(e) => { eprintln!("opus encode error: {e}"); continue; }
+                                    },
+                                    None => bytemuck::cast_slice(&chunk).to_vec(),
+                                };
+                                // Use try_send to avoid blocking the audio thread
+                                let _ = frame_tx.try_send(Bytes::from(bytes));
+                            }
                         }
                     },
                     move |err| {
@@ -179,39 +372,23 @@ pub async fn start_stream(ws_url: &str, cfg: AudioConfig) -> Result<StreamHandle
 
             // Build output stream
             let output_stream = {
-                let play_rx = play_rx.clone();
+                let mut jitter_reader = jitter_reader;
                 match output_sample_format {
                     SampleFormat::I16 => {
+                        let mut mono_scratch: Vec<i16> = Vec::new();
                         output_device.build_output_stream(
                             &output_config,
                             move |output: &mut [i16], _| {
                                 let channels = output_config.channels as usize;
+                                let mono_len = output.len() / channels.max(1);
+                                mono_scratch.resize(mono_len, 0);
+                                jitter_reader.fill(&mut mono_scratch);
                                 let mut idx = 0usize;
-                                if let Ok(mut guard) = play_rx.try_lock() {
-                                    // Fill buffer with as many frames as available; duplicate mono→stereo if needed
-                                    while idx < output.len() {
-                                        match guard.try_recv() {
-                                            Ok(bytes) => {
-                                                let mono_samples: &[i16] = bytemuck::cast_slice(&bytes);
-                                                for &s in mono_samples {
-                                                    if channels == 1 {
-                                                        if idx < output.len() { output[idx] = s; idx += 1; } else { break; }
-                                                    } else {
-                                                        // duplicate into all channels
-                                                        for _c in 0..channels {
-                                                            if idx < output.len() { output[idx] = s; idx += 1; } else { break; }
-                                                        }
-                                                    }
-                                                }
-                                            }
-                                            Err(_) => {
-                                                while idx < output.len() { output[idx] = 0; idx += 1; }
-                                                break;
-                                            }
-                                        }
+                                for &s in mono_scratch.iter() {
+                                    // Duplicate mono -> every output channel
+                                    for _c in 0..channels {
+                                        if idx < output.len() { output[idx] = s; idx += 1; } else { break; }
                                     }
-                                } else {
-                                    for sample in output.iter_mut() { *sample = 0; }
                                 }
                             },
                             move |err| { eprintln!("output stream error: {err}"); },
@@ -219,35 +396,20 @@ pub async fn start_stream(ws_url: &str, cfg: AudioConfig) -> Result<StreamHandle
                         ).expect("build_output_stream failed")
                     }
                     SampleFormat::F32 => {
+                        let mut mono_scratch: Vec<i16> = Vec::new();
                         output_device.build_output_stream(
                             &output_config,
                             move |output: &mut [f32], _| {
                                 let channels = output_config.channels as usize;
+                                let mono_len = output.len() / channels.max(1);
+                                mono_scratch.resize(mono_len, 0);
+                                jitter_reader.fill(&mut mono_scratch);
                                 let mut idx = 0usize;
-                                if let Ok(mut guard) = play_rx.try_lock() {
-                                    while idx < output.len() {
-                                        match guard.try_recv() {
-                                            Ok(bytes) => {
-                                                let mono_i16: &[i16] = bytemuck::cast_slice(&bytes);
-                                                for &s in mono_i16 {
-                                                    let v = (s as f32) / 32768.0;
-                                                    if channels == 1 {
-                                                        if idx < output.len() { output[idx] = v; idx += 1; } else { break; }
-                                                    } else {
-                                                        for _c in 0..channels {
-                                                            if idx < output.len() { output[idx] = v; idx += 1; } else { break; }
-                                                        }
-                                                    }
-                                                }
-                                            }
-                                            Err(_) => {
-                                                while idx < output.len() { output[idx] = 0.0; idx += 1; }
-                                                break;
-                                            }
-                                        }
+                                for &s in mono_scratch.iter() {
+                                    let v = (s as f32) / i16::MAX as f32;
+                                    for _c in 0..channels {
+                                        if idx < output.len() { output[idx] = v; idx += 1; } else { break; }
                                     }
-                                } else {
-                                    for sample in output.iter_mut() { *sample = 0.0; }
                                 }
                             },
                             move |err| { eprintln!("output stream error: {err}"); },
@@ -264,38 +426,46 @@ pub async fn start_stream(ws_url: &str, cfg: AudioConfig) -> Result<StreamHandle
             if let Err(e) = input_stream.play() { eprintln!("failed to play input stream: {e}"); return; }
             if let Err(e) = output_stream.play() { eprintln!("failed to play output stream: {e}"); return; }
 
-            // Connect websocket and run main loop
-            let (ws_stream, _resp) = match connect_async(&ws_url_owned).await {
+            // Connect the transport and run the main loop
+            let (mut writer, mut reader, encrypted) = match transport::connect(&transport_config).await {
                 Ok(v) => v,
-                Err(e) => { eprintln!("ws connect error: {e}"); return; }
+                Err(e) => { eprintln!("transport connect error: {e}"); return; }
             };
-            let (mut ws_writer, mut ws_reader) = ws_stream.split();
 
+            let (format, bitrate) = match cfg.codec {
+                AudioCodec::Pcm => ("S16LE", None),
+                AudioCodec::Opus { bitrate } => ("opus", Some(bitrate)),
+            };
             let init = InitMessage {
                 r#type: "init",
                 sample_rate: cfg.sample_rate,
                 channels: cfg.channels,
-                format: "S16LE",
+                format,
                 frame_size: cfg.frame_size,
+                bitrate,
+                encrypted,
             };
-            if let Err(e) = ws_writer
-                .send(tokio_tungstenite::tungstenite::Message::Text(serde_json::to_string(&init).unwrap()))
-                .await
-            {
-                eprintln!("ws send init error: {e}");
+            if let Err(e) = writer.send_control(serde_json::to_string(&init).unwrap()).await {
+                eprintln!("transport send init error: {e}");
                 return;
             }
 
-            // Reader task to forward audio to playback queue
-            let reader_play_tx = play_tx.clone();
+            // Reader task to decode incoming audio into the playback jitter buffer
+            let decode_frame_size = (cfg.frame_size * cfg.channels as u32) as usize;
             let reader_task = tokio::spawn(async move {
-                while let Some(msg) = ws_reader.next().await {
-                    match msg {
-                        Ok(tokio_tungstenite::tungstenite::Message::Binary(data)) => {
-                            let _ = reader_play_tx.send(Bytes::from(data)).await;
-                        }
-                        Ok(_) => {}
-                        Err(e) => { eprintln!("ws read error: {e}"); break; }
+                let mut pcm_buf = vec![0i16; decode_frame_size];
+                loop {
+                    let data = match reader.recv_audio().await {
+                        Ok(Some(data)) => data,
+                        Ok(None) => break,
+                        Err(e) => { eprintln!("transport recv_audio error: {e}"); break; }
+                    };
+                    match opus_decoder.as_mut() {
+                        Some(dec) => match dec.decode(Some(&data), &mut pcm_buf, false) {
+                            Ok(n) => jitter_writer.push(&pcm_buf[..n]),
+                            Err(e) => { eprintln!("opus decode error: {e}"); }
+                        },
+                        None => jitter_writer.push(bytemuck::cast_slice(&data)),
                     }
                 }
             });
@@ -316,8 +486,8 @@ pub async fn start_stream(ws_url: &str, cfg: AudioConfig) -> Result<StreamHandle
                         if let Some(frame) = maybe {
                             total_frames += 1;
                             total_bytes += frame.len() as u64;
-                            if let Err(e) = ws_writer.send(tokio_tungstenite::tungstenite::Message::Binary(frame.to_vec())).await {
-                                eprintln!("ws write error: {e}");
+                            if let Err(e) = writer.send_audio(frame).await {
+                                eprintln!("transport send_audio error: {e}");
                                 break;
                             }
                         }
@@ -328,15 +498,17 @@ pub async fn start_stream(ws_url: &str, cfg: AudioConfig) -> Result<StreamHandle
 
                 if last_stats.elapsed() >= std::time::Duration::from_secs(1) {
                     let elapsed = start_time.elapsed().as_secs_f64();
+                    let buffer_fill_ms = jitter_fill.samples() as f64
+                        / (cfg.sample_rate as f64 * cfg.channels as f64)
+                        * 1000.0;
                     let stats = serde_json::json!({
                         "type": "stats",
                         "elapsedSec": (elapsed * 1000.0).round() / 1000.0,
                         "frames": total_frames,
                         "bytes": total_bytes,
+                        "bufferFillMs": (buffer_fill_ms * 10.0).round() / 10.0,
                     });
-                    let _ = ws_writer
-                        .send(tokio_tungstenite::tungstenite::Message::Text(stats.to_string()))
-                        .await;
+                    let _ = writer.send_control(stats.to_string()).await;
                     last_stats = std::time::Instant::now();
                 }
             }
@@ -0,0 +1,159 @@
+//! Local pitch/formant shifting so the client can change a voice without a
+//! server in the loop. Implemented as a streaming phase vocoder: overlapping
+//! analysis windows are FFT'd, the true instantaneous frequency of each bin
+//! is tracked across frames, and the signal is resynthesized at a stretched
+//! hop before being linearly resampled back to its original duration.
+
+use realfft::num_complex::Complex32;
+use realfft::{ComplexToReal, RealFftPlanner, RealToComplex};
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+const FRAME_SIZE: usize = 1024; // N
+const ANALYSIS_HOP: usize = 256; // H
+
+/// Streaming phase-vocoder pitch shifter for mono `i16` audio.
+///
+/// Callers feed arbitrarily-sized chunks via [`PhaseVocoder::process`] and
+/// get back the same (approximate) number of pitch-shifted samples; all
+/// windowing, phase tracking, and resampling state persists across calls so
+/// frames stitch together continuously.
+pub struct PhaseVocoder {
+    ratio: f32, // 2^(semitones/12)
+    window: Vec<f32>,
+    r2c: Arc<dyn RealToComplex<f32>>,
+    c2r: Arc<dyn ComplexToReal<f32>>,
+
+    input_queue: VecDeque<f32>,
+    prev_phase: Vec<f32>,
+    sum_phase: Vec<f32>,
+
+    // Fixed-size overlap-add buffer; shifted left by `stretched_hop` samples
+    // (rounded from ANALYSIS_HOP * ratio) after every analysis/synthesis step.
+    ola_buf: Vec<f32>,
+    // Time-stretched synthesis output waiting to be resampled by 1/ratio.
+    stretched_ready: VecDeque<f32>,
+    resample_pos: f64,
+}
+
+impl PhaseVocoder {
+    pub fn new(pitch_shift_semitones: f32) -> Self {
+        let mut planner = RealFftPlanner::<f32>::new();
+        let window = hann_window(FRAME_SIZE);
+        let bins = FRAME_SIZE / 2 + 1;
+        Self {
+            ratio: 2f32.powf(pitch_shift_semitones / 12.0),
+            window,
+            r2c: planner.plan_fft_forward(FRAME_SIZE),
+            c2r: planner.plan_fft_inverse(FRAME_SIZE),
+            input_queue: VecDeque::new(),
+            prev_phase: vec![0.0; bins],
+            sum_phase: vec![0.0; bins],
+            ola_buf: vec![0.0; FRAME_SIZE],
+            stretched_ready: VecDeque::new(),
+            resample_pos: 0.0,
+        }
+    }
+
+    /// Pitch-shift `input` and return the resampled output accumulated so
+    /// far. The output length tracks the input length but is not guaranteed
+    /// to match it sample-for-sample on any given call, since the vocoder
+    /// only emits output once a full analysis frame is available.
+    pub fn process(&mut self, input: &[i16]) -> Vec<i16> {
+        if (self.ratio - 1.0).abs() < f32::EPSILON {
+            return input.to_vec();
+        }
+
+        for &s in input {
+            self.input_queue.push_back(s as f32 / i16::MAX as f32);
+        }
+
+        while self.input_queue.len() >= FRAME_SIZE {
+            self.analyze_and_synthesize();
+            self.input_queue.drain(..ANALYSIS_HOP);
+        }
+
+        self.resample_ready()
+    }
+
+    fn analyze_and_synthesize(&mut self) {
+        let mut frame: Vec<f32> = self
+            .input_queue
+            .iter()
+            .take(FRAME_SIZE)
+            .zip(self.window.iter())
+            .map(|(s, w)| s * w)
+            .collect();
+
+        let mut spectrum = self.r2c.make_output_vec();
+        if self.r2c.process(&mut frame, &mut spectrum).is_err() {
+            return;
+        }
+
+        for (k, bin) in spectrum.iter_mut().enumerate() {
+            let magnitude = bin.norm();
+            let phase = bin.arg();
+
+            let delta = phase - self.prev_phase[k];
+            self.prev_phase[k] = phase;
+
+            let expected = 2.0 * PI * k as f32 * ANALYSIS_HOP as f32 / FRAME_SIZE as f32;
+            let deviation = wrap_phase(delta - expected);
+            let true_freq = 2.0 * PI * k as f32 / FRAME_SIZE as f32 + deviation / ANALYSIS_HOP as f32;
+
+            self.sum_phase[k] += true_freq * (ANALYSIS_HOP as f32 * self.ratio);
+            *bin = Complex32::from_polar(magnitude, self.sum_phase[k]);
+        }
+
+        let mut synth_frame = self.c2r.make_output_vec();
+        if self.c2r.process(&mut spectrum, &mut synth_frame).is_err() {
+            return;
+        }
+
+        // realfft's inverse transform is unnormalized.
+        let norm = 1.0 / FRAME_SIZE as f32;
+        let stretched_hop = ((ANALYSIS_HOP as f32 * self.ratio).round() as usize).max(1);
+
+        for i in 0..FRAME_SIZE {
+            self.ola_buf[i] += synth_frame[i] * self.window[i] * norm;
+        }
+
+        let finalized = stretched_hop.min(self.ola_buf.len());
+        self.stretched_ready.extend(self.ola_buf.drain(..finalized));
+        self.ola_buf.resize(FRAME_SIZE, 0.0);
+    }
+
+    /// Linearly resample the time-stretched signal by `1/ratio`, emitting
+    /// fixed-duration output and clamping back to `i16`.
+    fn resample_ready(&mut self) -> Vec<i16> {
+        let mut out = Vec::new();
+        while (self.resample_pos.floor() as usize + 1) < self.stretched_ready.len() {
+            let idx = self.resample_pos.floor() as usize;
+            let frac = (self.resample_pos - idx as f64) as f32;
+            let s0 = self.stretched_ready[idx];
+            let s1 = self.stretched_ready[idx + 1];
+            let v = s0 + (s1 - s0) * frac;
+            out.push((v * i16::MAX as f32).clamp(i16::MIN as f32, i16::MAX as f32) as i16);
+            self.resample_pos += self.ratio as f64;
+        }
+
+        let drop_n = (self.resample_pos.floor() as usize).min(self.stretched_ready.len());
+        if drop_n > 0 {
+            self.stretched_ready.drain(..drop_n);
+            self.resample_pos -= drop_n as f64;
+        }
+
+        out
+    }
+}
+
+fn hann_window(len: usize) -> Vec<f32> {
+    (0..len)
+        .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / (len as f32 - 1.0)).cos())
+        .collect()
+}
+
+fn wrap_phase(phase: f32) -> f32 {
+    (phase + PI).rem_euclid(2.0 * PI) - PI
+}
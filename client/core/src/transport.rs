@@ -0,0 +1,170 @@
+//! Pluggable network transport for the audio stream.
+//!
+//! `start_stream` used to be welded directly to `tokio_tungstenite`. This
+//! module pulls that behind a small `send_audio`/`send_control`/`recv_audio`
+//! surface so other protocols can be added later, and layers an optional
+//! lightweight XOR-keystream cipher on top of any transport's binary
+//! payloads so the wire format doesn't have to ship in the clear.
+
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use futures_util::stream::{SplitSink, SplitStream};
+use futures_util::{SinkExt, StreamExt};
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Describes how `start_stream` should reach the relay server.
+#[derive(Clone, Debug)]
+pub enum TransportConfig {
+    WebSocket {
+        url: String,
+        /// Pre-shared key driving the XOR keystream; `None` sends plaintext.
+        psk: Option<Vec<u8>>,
+    },
+}
+
+/// A counter-seeded XOR keystream cipher. This is not cryptographically
+/// strong -- it exists to keep casual network sniffing from reading S16LE/
+/// Opus frames off the wire, not to resist a motivated attacker.
+#[derive(Clone)]
+struct XorCipher {
+    key: Vec<u8>,
+    counter: u64,
+}
+
+impl XorCipher {
+    fn new(key: Vec<u8>) -> Self {
+        Self { key, counter: 0 }
+    }
+
+    fn keystream_byte(&self, nonce: u64, i: usize) -> u8 {
+        let key_byte = self.key[i % self.key.len()];
+        let nonce_bytes = nonce.wrapping_add((i / 8) as u64).to_le_bytes();
+        key_byte ^ nonce_bytes[i % 8]
+    }
+
+    /// Encrypt `payload`, prepending the 8-byte nonce used to seed the
+    /// keystream so the receiving side can resync per-message.
+    fn seal(&mut self, payload: &[u8]) -> Vec<u8> {
+        let nonce = self.counter;
+        self.counter = self.counter.wrapping_add(1);
+        let mut out = Vec::with_capacity(8 + payload.len());
+        out.extend_from_slice(&nonce.to_le_bytes());
+        out.extend(
+            payload
+                .iter()
+                .enumerate()
+                .map(|(i, &b)| b ^ self.keystream_byte(nonce, i)),
+        );
+        out
+    }
+
+    /// Decrypt a payload produced by `seal`, reading the nonce back out of
+    /// its first 8 bytes.
+    fn open(&self, payload: &[u8]) -> Result<Vec<u8>> {
+        if payload.len() < 8 {
+            return Err(anyhow!("encrypted payload shorter than the nonce"));
+        }
+        let nonce = u64::from_le_bytes(payload[..8].try_into().unwrap());
+        Ok(payload[8..]
+            .iter()
+            .enumerate()
+            .map(|(i, &b)| b ^ self.keystream_byte(nonce, i))
+            .collect())
+    }
+}
+
+enum WriterInner {
+    WebSocket(SplitSink<WsStream, Message>),
+}
+
+enum ReaderInner {
+    WebSocket(SplitStream<WsStream>),
+}
+
+pub struct TransportWriter {
+    inner: WriterInner,
+    cipher: Option<XorCipher>,
+}
+
+pub struct TransportReader {
+    inner: ReaderInner,
+    cipher: Option<XorCipher>,
+}
+
+impl TransportWriter {
+    pub async fn send_audio(&mut self, payload: Bytes) -> Result<()> {
+        let bytes = match self.cipher.as_mut() {
+            Some(cipher) => cipher.seal(&payload),
+            None => payload.to_vec(),
+        };
+        match &mut self.inner {
+            WriterInner::WebSocket(sink) => sink
+                .send(Message::Binary(bytes))
+                .await
+                .map_err(|e| anyhow!("transport send_audio error: {e}")),
+        }
+    }
+
+    pub async fn send_control(&mut self, text: String) -> Result<()> {
+        match &mut self.inner {
+            WriterInner::WebSocket(sink) => sink
+                .send(Message::Text(text))
+                .await
+                .map_err(|e| anyhow!("transport send_control error: {e}")),
+        }
+    }
+}
+
+impl TransportReader {
+    /// Await the next audio payload, decrypting it if a cipher is
+    /// configured. Returns `Ok(None)` once the underlying transport closes.
+    pub async fn recv_audio(&mut self) -> Result<Option<Bytes>> {
+        loop {
+            let msg = match &mut self.inner {
+                ReaderInner::WebSocket(stream) => stream.next().await,
+            };
+            match msg {
+                None => return Ok(None),
+                Some(Ok(Message::Binary(data))) => {
+                    let data = match self.cipher.as_ref() {
+                        Some(cipher) => cipher.open(&data)?,
+                        None => data,
+                    };
+                    return Ok(Some(Bytes::from(data)));
+                }
+                Some(Ok(_)) => continue,
+                Some(Err(e)) => return Err(anyhow!("transport recv_audio error: {e}")),
+            }
+        }
+    }
+}
+
+/// Connect the configured transport, returning split read/write halves plus
+/// whether encryption ended up active (for the `InitMessage` handshake).
+pub async fn connect(config: &TransportConfig) -> Result<(TransportWriter, TransportReader, bool)> {
+    match config {
+        TransportConfig::WebSocket { url, psk } => {
+            let (ws_stream, _resp) = connect_async(url)
+                .await
+                .map_err(|e| anyhow!("ws connect error: {e}"))?;
+            let (sink, stream) = ws_stream.split();
+            // An empty key is the same as "no key" -- normalize here so the
+            // cipher never has to divide by a zero-length key.
+            let psk = psk.as_ref().filter(|k| !k.is_empty());
+            let encrypted = psk.is_some();
+            let writer = TransportWriter {
+                inner: WriterInner::WebSocket(sink),
+                cipher: psk.cloned().map(XorCipher::new),
+            };
+            let reader = TransportReader {
+                inner: ReaderInner::WebSocket(stream),
+                cipher: psk.cloned().map(XorCipher::new),
+            };
+            Ok((writer, reader, encrypted))
+        }
+    }
+}